@@ -2,15 +2,20 @@ use failure::{err_msg, format_err, Error};
 use log::debug;
 use serde::Deserialize;
 
-use std::path::PathBuf;
-use std::process::Command;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
 use std::{iter, str};
 
-const DEFAULT_CARGO_ARGS: &[&str] = &["--message-format=json", "--quiet"];
+const DEFAULT_CARGO_ARGS: &[&str] = &["--message-format=json-render-diagnostics"];
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) enum CmdKind {
     Run,
+    // Like `Run`, but invoked directly as `cargo build` by the user (e.g.
+    // `cargo with nm -- build --lib`) rather than inferred from `run`. Useful
+    // to target library artifacts, which can't be `cargo run`.
+    Build,
     Test,
     Bench,
 }
@@ -21,6 +26,7 @@ impl CmdKind {
         use self::CmdKind::*;
         match s {
             "run" => Some(Run),
+            "build" => Some(Build),
             "test" => Some(Test),
             "bench" => Some(Bench),
             _ => None,
@@ -31,6 +37,7 @@ impl CmdKind {
     fn as_artifact_cmd(self) -> &'static str {
         match self {
             CmdKind::Run => "build",
+            CmdKind::Build => "build",
             CmdKind::Test => "test",
             CmdKind::Bench => "bench",
         }
@@ -65,6 +72,60 @@ impl<'a> Cmd<'a> {
     pub(crate) fn kind(&self) -> CmdKind {
         self.kind
     }
+    /// The `--target <triple>` passed to the cargo command, if any. Accepts
+    /// both the `--target <triple>` and `--target=<triple>` forms cargo
+    /// itself understands.
+    pub(crate) fn target_triple(&self) -> Option<&'a str> {
+        let mut args = self.args.iter();
+        while let Some(&arg) = args.next() {
+            if let Some(triple) = arg.strip_prefix("--target=") {
+                return Some(triple);
+            }
+            if arg == "--target" {
+                return args.next().copied();
+            }
+        }
+        None
+    }
+    /// The `--bin`/`--example`/`--test`/`--bench <name>` target selector
+    /// passed to the cargo command, if any, e.g. `cargo with gdb -- run
+    /// --bin my-bin`. Accepts both the `--flag <name>` and `--flag=<name>`
+    /// forms cargo itself understands.
+    pub(crate) fn target_selector(&self) -> Option<(TargetKind, &'a str)> {
+        const FLAGS: &[(&str, TargetKind)] = &[
+            ("--bin", TargetKind::Bin),
+            ("--example", TargetKind::Example),
+            ("--test", TargetKind::Test),
+            ("--bench", TargetKind::Bench),
+        ];
+        let mut args = self.args.iter();
+        while let Some(&arg) = args.next() {
+            for &(flag, kind) in FLAGS {
+                if let Some(name) = arg.strip_prefix(flag).and_then(|s| s.strip_prefix('=')) {
+                    return Some((kind, name));
+                }
+                if arg == flag {
+                    return args.next().copied().map(|name| (kind, name));
+                }
+            }
+        }
+        None
+    }
+    /// The `-p`/`--package <pkg>` passed to the cargo command, if any.
+    /// Accepts the `-p <pkg>`, `--package <pkg>` and `--package=<pkg>` forms
+    /// cargo itself understands.
+    pub(crate) fn package_name(&self) -> Option<&'a str> {
+        let mut args = self.args.iter();
+        while let Some(&arg) = args.next() {
+            if let Some(pkg) = arg.strip_prefix("--package=") {
+                return Some(pkg);
+            }
+            if arg == "-p" || arg == "--package" {
+                return args.next().copied();
+            }
+        }
+        None
+    }
     /// Get the arguments which would be passed to `cargo`
     ///
     /// Includes the type of command (e.g `test`, `run`), the default arguments
@@ -90,9 +151,15 @@ impl<'a> Cmd<'a> {
             self.args().collect::<Vec<_>>().join(" ")
         );
 
-        let build_out = Command::new("cargo")
+        // Only stdout (the JSON message stream) is piped; stderr is
+        // inherited so Cargo's own build progress and pre-rendered
+        // diagnostics (see `json-render-diagnostics` in
+        // `DEFAULT_CARGO_ARGS`) show up live instead of only being printed
+        // after the whole build finishes.
+        let mut child = Command::new("cargo")
             .args(self.args())
-            .output()
+            .stdout(Stdio::piped())
+            .spawn()
             .map_err(|_| {
                 format_err!(
                     "Unable to run cargo command: `cargo {}`",
@@ -100,48 +167,108 @@ impl<'a> Cmd<'a> {
                 )
             })?;
 
-        if !build_out.status.success() {
-            Err(format_err!(
-                "{}\n{}\nCargo subcommand failed. Try running the original cargo command (without cargo-with)",
-                str::from_utf8(&build_out.stderr).unwrap(),
-                str::from_utf8(&build_out.stdout).unwrap()
-            ))?;
-        }
+        let stdout = child
+            .stdout
+            .take()
+            .expect("Child was spawned with a piped stdout");
 
-        let opts = str::from_utf8(&build_out.stdout)
-            .map_err(|_| {
+        let mut artifacts = vec![];
+        let mut build_finished = None;
+        for line in BufReader::new(stdout).lines() {
+            let line = line.map_err(|_| {
                 format_err!(
                     "Output of `cargo {}` contained invalid UTF-8 characters",
                     self.args().collect::<Vec<_>>().join(" ")
                 )
-            })?
-            .lines()
-            // FIXME: There are plenty of errors here! This should really be better handled!
-            .flat_map(serde_json::from_str::<BuildOpt>)
-            .collect();
+            })?;
+            match serde_json::from_str::<CargoMessage>(&line) {
+                Ok(CargoMessage::CompilerArtifact(opt)) => artifacts.push(opt),
+                Ok(CargoMessage::CompilerMessage { message, .. }) => {
+                    if let Some(rendered) = message.rendered {
+                        eprint!("{}", rendered);
+                    }
+                }
+                Ok(CargoMessage::BuildFinished { success }) => build_finished = Some(success),
+                Ok(CargoMessage::BuildScriptExecuted { .. }) | Ok(CargoMessage::Unknown) => (),
+                // Cargo's JSON output is line-delimited; tolerate stray lines
+                // (e.g. from a build script) that aren't valid messages.
+                Err(_) => (),
+            }
+        }
+
+        let status = child
+            .wait()
+            .map_err(|e| format_err!("Failed to wait on `cargo`: {}", e))?;
 
-        Ok(opts)
+        // Don't just rely on the process exit code: a `build-finished` message
+        // reporting failure is the authoritative signal, and lets us error out
+        // with the diagnostics we already printed above instead of a raw dump
+        // of stdout/stderr.
+        if build_finished == Some(false) {
+            Err(err_msg(
+                "Cargo reported a build failure. See the diagnostics above for details.",
+            ))?;
+        } else if !status.success() {
+            Err(format_err!(
+                "Cargo subcommand failed. Try running the original cargo command (without cargo-with)"
+            ))?;
+        }
+
+        Ok(artifacts)
     }
 }
 
+/// One line of Cargo's `--message-format=json` output. Tagged by `reason`,
+/// with an `Unknown` catch-all so that message kinds added by future Cargo
+/// versions don't break parsing.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "reason", rename_all = "kebab-case")]
+enum CargoMessage {
+    CompilerArtifact(BuildOpt),
+    CompilerMessage {
+        #[allow(dead_code)]
+        package_id: String,
+        #[allow(dead_code)]
+        target: Target,
+        message: Diagnostic,
+    },
+    BuildScriptExecuted {
+        #[allow(dead_code)]
+        package_id: String,
+    },
+    BuildFinished {
+        success: bool,
+    },
+    #[serde(other)]
+    Unknown,
+}
+
+/// The rendered diagnostic text for a `compiler-message`, e.g. a warning or
+/// error produced while compiling a crate.
+#[derive(Deserialize, Debug)]
+struct Diagnostic {
+    rendered: Option<String>,
+    #[allow(dead_code)]
+    level: String,
+}
+
 #[derive(Deserialize, Debug)]
 pub(crate) struct BuildOpt {
     features: Vec<String>,
-    filenames: Vec<PathBuf>,
+    pub(crate) filenames: Vec<PathBuf>,
     fresh: bool,
     package_id: String,
-    profile: Profile,
-    reason: String,
-    target: Target,
+    pub(crate) profile: Profile,
+    pub(crate) target: Target,
 }
 
 #[derive(Deserialize, Debug)]
-struct Profile {
+pub(crate) struct Profile {
     debug_assertions: bool,
     debuginfo: Option<u32>,
     opt_level: String,
     overflow_checks: bool,
-    test: bool,
+    pub(crate) test: bool,
 }
 
 /// Most possible targetkinds taken from
@@ -151,13 +278,15 @@ struct Profile {
 /// signature).
 #[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 #[serde(rename_all = "kebab-case")]
-enum TargetKind {
+pub(crate) enum TargetKind {
     Example,
     Test,
     Bin,
     Lib,
     Rlib,
     Dylib,
+    Cdylib,
+    Staticlib,
     ProcMacro,
     Bench,
     CustomBuild,
@@ -172,6 +301,8 @@ impl std::fmt::Display for TargetKind {
             TargetKind::Lib => "lib",
             TargetKind::Rlib => "rlib",
             TargetKind::Dylib => "dylib",
+            TargetKind::Cdylib => "cdylib",
+            TargetKind::Staticlib => "staticlib",
             TargetKind::ProcMacro => "proc-macro",
             TargetKind::Bench => "bench",
             TargetKind::CustomBuild => "custom-build",
@@ -180,43 +311,87 @@ impl std::fmt::Display for TargetKind {
     }
 }
 
+/// The `--crate-type` selector. Mirrors `rustc`'s `--crate-type` values for
+/// the crate types that can be built as a library artifact.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CrateType {
+    Lib,
+    Rlib,
+    Dylib,
+    Cdylib,
+    Staticlib,
+}
+
+impl CrateType {
+    /// Turns a string into a `CrateType`
+    pub(crate) fn from_str(s: &str) -> Option<Self> {
+        use self::CrateType::*;
+        match s {
+            "lib" => Some(Lib),
+            "rlib" => Some(Rlib),
+            "dylib" => Some(Dylib),
+            "cdylib" => Some(Cdylib),
+            "staticlib" => Some(Staticlib),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            CrateType::Lib => "lib",
+            CrateType::Rlib => "rlib",
+            CrateType::Dylib => "dylib",
+            CrateType::Cdylib => "cdylib",
+            CrateType::Staticlib => "staticlib",
+        }
+    }
+}
+
 #[derive(Deserialize, Debug)]
-struct Target {
-    crate_types: Vec<String>,
+pub(crate) struct Target {
+    pub(crate) crate_types: Vec<String>,
     edition: String,
-    kind: Vec<TargetKind>,
-    name: String,
+    pub(crate) kind: Vec<TargetKind>,
+    pub(crate) name: String,
     src_path: PathBuf,
 }
 
+/// Bundles the various ways a caller can narrow down which `BuildOpt`s are
+/// acceptable candidates. Grouped into one struct since the list of knobs
+/// keeps growing (crate type, target triple, ...) and threading them through
+/// `select_buildopt`/`select_all_buildopts`/`matching_buildopts` individually
+/// gets unwieldy.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct SelectFilter<'a> {
+    pub(crate) crate_type: Option<CrateType>,
+    /// When set, only artifacts built for this `--target` triple are
+    /// considered, i.e. ones whose path lives under `target/<triple>/`.
+    pub(crate) target_triple: Option<&'a str>,
+    /// When set (from a `--bin`/`--example`/`--test`/`--bench <name>` passed
+    /// to the cargo command), only the artifact with this exact kind and
+    /// `Target::name` is considered.
+    pub(crate) target_selector: Option<(TargetKind, &'a str)>,
+    /// When set (from a `-p`/`--package <pkg>` passed to the cargo command),
+    /// only artifacts belonging to this package are considered. Lets
+    /// `--workspace` builds that produce several runnable artifacts across
+    /// members be narrowed down to a single one.
+    pub(crate) package: Option<&'a str>,
+}
+
 /// Selects the buildopt which fits with the requirements
 ///
 /// If there are multiple possible candidates, this will return an error
 pub(crate) fn select_buildopt<'a>(
-    opts: impl IntoIterator<Item = &'a BuildOpt>,
+    opts: impl IntoIterator<Item = &'a BuildOpt> + Clone,
     cmd_kind: CmdKind,
+    filter: SelectFilter<'_>,
 ) -> Result<&'a BuildOpt, Error> {
-    // Target kinds we want to look for
-    let look_for = &[TargetKind::Bin, TargetKind::Example, TargetKind::Test];
-
-    // Find candidates with the possible target types
-    let candidates: Vec<_> = opts
-        .into_iter()
-        .filter(|opt| {
-            // When run as a test or bench we only care about the
-            // binary where the profile is set as `test`
-            match cmd_kind {
-                CmdKind::Test | CmdKind::Bench => opt.profile.test,
-                CmdKind::Run => opt
-                    .target
-                    .kind
-                    .iter()
-                    .any(|kind| look_for.iter().any(|lkind| lkind == kind)),
-            }
-        })
-        .collect();
+    let candidates = matching_buildopts(opts.clone(), cmd_kind, filter);
     // We expect exactly one candidate; everything else is an error
     match candidates.as_slice() {
+        [] if filter.target_selector.is_some() || filter.package.is_some() => {
+            Err(no_selector_match_err(opts, cmd_kind, filter))
+        }
         [] => Err(err_msg("No suitable build artifacts found.")),
         [the_one] => Ok(the_one),
         the_many => Err(format_err!(
@@ -226,10 +401,334 @@ pub(crate) fn select_buildopt<'a>(
     }
 }
 
+/// Builds the error for a `--bin`/`--example`/`--test`/`--bench <name>` or
+/// `-p <pkg>` selector that matched nothing, listing the available target
+/// names the way Cargo's own `print_available_binaries` does.
+fn no_selector_match_err<'a>(
+    opts: impl IntoIterator<Item = &'a BuildOpt>,
+    cmd_kind: CmdKind,
+    filter: SelectFilter<'_>,
+) -> Error {
+    let mut unfiltered = filter;
+    unfiltered.target_selector = None;
+    unfiltered.package = None;
+    let available = matching_buildopts(opts, cmd_kind, unfiltered);
+    let names = available
+        .iter()
+        .map(|opt| format!("\t{} ({})", opt.package_name(), opt.target.name))
+        .collect::<Vec<_>>()
+        .join("\n");
+    format_err!(
+        "No artifact matches the requested target/package selector.\nAvailable targets:\n{}",
+        names
+    )
+}
+
+/// Like [`select_buildopt`], but returns every matching candidate instead of
+/// erroring out when there is more than one. Used by the `--all` mode, where
+/// the with-command is run once per matching artifact.
+pub(crate) fn select_all_buildopts<'a>(
+    opts: impl IntoIterator<Item = &'a BuildOpt>,
+    cmd_kind: CmdKind,
+    filter: SelectFilter<'_>,
+) -> Result<Vec<&'a BuildOpt>, Error> {
+    let candidates = matching_buildopts(opts, cmd_kind, filter);
+    if candidates.is_empty() {
+        Err(err_msg("No suitable build artifacts found."))?
+    }
+    Ok(candidates)
+}
+
+/// Filters `opts` down to the ones matching `cmd_kind`, without judging how
+/// many of them there are.
+///
+/// For `build`, library artifacts (`rlib`/`cdylib`/`staticlib`/`dylib`)
+/// are candidates in addition to the usual `bin`/`example`/`test`
+/// executables. When `filter.crate_type` is given, candidates are
+/// additionally required to produce that crate type (e.g. `cdylib`), so
+/// executables are then left out. When `filter.target_triple`
+/// is given, candidates are further narrowed down to artifacts built for
+/// that `--target`. When `filter.target_selector` is given, candidates are
+/// narrowed down to the single target with that kind and name.
+fn matching_buildopts<'a>(
+    opts: impl IntoIterator<Item = &'a BuildOpt>,
+    cmd_kind: CmdKind,
+    filter: SelectFilter<'_>,
+) -> Vec<&'a BuildOpt> {
+    // Target kinds we want to look for. Library kinds are always considered
+    // for `build` (e.g. `cargo with "llvm-objdump -d" -- build --lib` needs
+    // to find the `rlib` without the user also passing `--crate-type`);
+    // `--crate-type` only comes into play afterwards, to keep only targets
+    // producing that crate type and to pick which of a target's several
+    // `filenames` to use. `run` never widens to library
+    // kinds: a bin crate's own lib target (when it has both a `src/lib.rs`
+    // and a `src/main.rs`) would otherwise show up as a second candidate
+    // alongside the bin, breaking plain `cargo with <tool> -- run` on a
+    // previously-unambiguous crate, and a library isn't runnable anyway.
+    const LIB_KINDS: &[TargetKind] = &[
+        TargetKind::Lib,
+        TargetKind::Rlib,
+        TargetKind::Dylib,
+        TargetKind::Cdylib,
+        TargetKind::Staticlib,
+    ];
+    // Only consulted from the `Run | Build` arm below; `Test`/`Bench` match
+    // on `profile.test` instead and never look at this.
+    let look_for: &[TargetKind] = match cmd_kind {
+        CmdKind::Build => &[
+            TargetKind::Bin,
+            TargetKind::Example,
+            TargetKind::Test,
+            TargetKind::Lib,
+            TargetKind::Rlib,
+            TargetKind::Dylib,
+            TargetKind::Cdylib,
+            TargetKind::Staticlib,
+        ],
+        CmdKind::Run | CmdKind::Test | CmdKind::Bench => {
+            &[TargetKind::Bin, TargetKind::Example, TargetKind::Test]
+        }
+    };
+
+    opts.into_iter()
+        .filter(|opt| {
+            // When run as a test or bench we only care about the
+            // binary where the profile is set as `test`
+            match cmd_kind {
+                CmdKind::Test | CmdKind::Bench => opt.profile.test,
+                CmdKind::Run | CmdKind::Build => {
+                    let is_lib_kind = opt
+                        .target
+                        .kind
+                        .iter()
+                        .any(|kind| LIB_KINDS.iter().any(|lkind| lkind == kind));
+                    // Library-kind artifacts from registry/git dependencies
+                    // share a `TargetKind` with whatever library the
+                    // package being built produces, but were never what the
+                    // user meant by `build --lib`, so they're excluded
+                    // unless the artifact is from a local package.
+                    let kind_matches = opt
+                        .target
+                        .kind
+                        .iter()
+                        .any(|kind| look_for.iter().any(|lkind| lkind == kind));
+                    kind_matches && (!is_lib_kind || opt.is_local_package())
+                }
+            }
+        })
+        .filter(|opt| match filter.crate_type {
+            None => true,
+            Some(crate_type) => opt
+                .target
+                .crate_types
+                .iter()
+                .any(|ct| ct == crate_type.as_str()),
+        })
+        .filter(|opt| match filter.target_triple {
+            None => true,
+            Some(triple) => opt
+                .filenames
+                .iter()
+                .any(|f| f.components().any(|c| c.as_os_str() == triple)),
+        })
+        .filter(|opt| match filter.target_selector {
+            None => true,
+            Some((kind, name)) => opt.target.name == name && opt.target.kind.contains(&kind),
+        })
+        .filter(|opt| match filter.package {
+            None => true,
+            Some(package) => opt.package_name() == package,
+        })
+        .collect()
+}
+
+/// Derives the unique parent directories of every collected artifact's
+/// `filenames`, as a flat `["-L", "dependency=<dir>", "-L", "dependency=<dir2>", ...]`
+/// list ready to hand to a wrapped tool that itself links against or loads
+/// Rust code (a custom test harness, a dynamic loader for a
+/// `dylib`/`proc-macro` artifact, ...) via the `{deps}` placeholder. Kept as
+/// separate entries rather than one joined string, since cargo-with spawns
+/// the wrapped command directly without a shell to re-split it.
+pub(crate) fn dep_search_paths(opts: &[BuildOpt]) -> Vec<String> {
+    let mut dirs: Vec<&Path> = vec![];
+    for opt in opts {
+        for filename in &opt.filenames {
+            if let Some(parent) = filename.parent() {
+                if !dirs.contains(&parent) {
+                    dirs.push(parent);
+                }
+            }
+        }
+    }
+    dirs.into_iter()
+        .flat_map(|dir| vec!["-L".to_owned(), format!("dependency={}", dir.display())])
+        .collect()
+}
+
+/// Shells out to `rustc --print cfg` to get the active `cfg` set Cargo
+/// built with (optionally for a foreign `--target`), as a flat
+/// `["--cfg", "<value>", "--cfg", "<value2>", ...]` list ready to hand to a
+/// wrapped tool that needs to conditionally compile with the same
+/// configuration, via the `{cfgs}` placeholder. Returns `None` if `rustc`
+/// can't be run or the `--target` isn't installed, the same way
+/// `configured_runner` silently gives up instead of failing the whole
+/// invocation over an optional convenience.
+pub(crate) fn target_cfgs(target_triple: Option<&str>) -> Option<Vec<String>> {
+    let mut cmd = Command::new("rustc");
+    cmd.arg("--print").arg("cfg");
+    if let Some(triple) = target_triple {
+        cmd.arg("--target").arg(triple);
+    }
+    let out = cmd.output().ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    Some(
+        str::from_utf8(&out.stdout)
+            .ok()?
+            .lines()
+            .flat_map(|cfg| vec!["--cfg".to_owned(), cfg.to_owned()])
+            .collect(),
+    )
+}
+
+/// Looks up the configured runner for a given `--target` triple, i.e. the
+/// `target.<triple>.runner` key Cargo itself reads out of `.cargo/config.toml`
+/// (or the older, extension-less `.cargo/config`) to know how to invoke
+/// foreign-architecture binaries (under QEMU, on a device over SSH, etc), as
+/// the individual argv entries to prepend to the with-command. Cargo
+/// accepts this key either as a plain string (`runner = "qemu-arm"`) or as
+/// an array giving the runner its own arguments (`runner = ["qemu-arm",
+/// "-L", "/usr/arm-linux-gnueabihf"]`); both forms are understood here.
+///
+/// This only understands the handful of lines needed to pull out that one
+/// key rather than pulling in a full TOML parser.
+pub(crate) fn configured_runner(triple: &str) -> Option<Vec<String>> {
+    let config_path = find_cargo_config(&std::env::current_dir().ok()?)?;
+    let contents = std::fs::read_to_string(config_path).ok()?;
+
+    let header = format!("[target.{}]", triple);
+    let mut in_section = false;
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            in_section = line == header;
+            continue;
+        }
+        if !in_section {
+            continue;
+        }
+        if let Some(value) = line.strip_prefix("runner").map(str::trim_start) {
+            if let Some(value) = value.strip_prefix('=') {
+                return parse_runner_value(value.trim());
+            }
+        }
+    }
+    None
+}
+
+/// Parses a `runner = ...` TOML value, in either its plain-string
+/// (`"qemu-arm"`) or array-of-strings (`["qemu-arm", "-L", "/path"]`) form,
+/// into the individual argv entries it denotes. Like Cargo, a plain string
+/// is split on whitespace.
+fn parse_runner_value(value: &str) -> Option<Vec<String>> {
+    match value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+        Some(elements) => Some(
+            elements
+                .split(',')
+                .map(str::trim)
+                .filter(|el| !el.is_empty())
+                .map(|el| el.trim_matches('"').to_owned())
+                .collect(),
+        ),
+        None => Some(
+            value
+                .trim_matches('"')
+                .split_whitespace()
+                .map(str::to_owned)
+                .collect(),
+        ),
+    }
+}
+
+/// Walks up from `start` looking for `.cargo/config.toml` or `.cargo/config`,
+/// the same way Cargo itself discovers its configuration.
+fn find_cargo_config(start: &Path) -> Option<PathBuf> {
+    let mut dir = start;
+    loop {
+        for name in &[".cargo/config.toml", ".cargo/config"] {
+            let candidate = dir.join(name);
+            if candidate.is_file() {
+                return Some(candidate);
+            }
+        }
+        dir = dir.parent()?;
+    }
+}
+
 impl BuildOpt {
     /// Best guess for the build artifact associated with this `BuildOpt`
-    pub(crate) fn artifact(&self) -> Result<PathBuf, Error> {
-        Ok(self.filenames[0].clone())
+    ///
+    /// When `crate_type` is given and this build produced several files
+    /// (e.g. both an `.rlib` and a `.so` for a crate built with several
+    /// `--crate-type`s), the filename lining up with that crate type is
+    /// picked instead of always taking the first one.
+    pub(crate) fn artifact(&self, crate_type: Option<CrateType>) -> Result<PathBuf, Error> {
+        let index = match crate_type {
+            None => 0,
+            Some(crate_type) => self
+                .target
+                .crate_types
+                .iter()
+                .position(|ct| ct == crate_type.as_str())
+                .ok_or_else(|| format_err!("Target does not produce a `{}`", crate_type.as_str()))?,
+        };
+        self.filenames
+            .get(index)
+            .cloned()
+            .ok_or_else(|| err_msg("No artifact file found for the selected crate type"))
+    }
+
+    /// Best-effort package name, extracted from `package_id`'s
+    /// `"name version (source)"` format.
+    pub(crate) fn package_name(&self) -> &str {
+        self.package_id
+            .split_whitespace()
+            .next()
+            .unwrap_or(&self.package_id)
+    }
+
+    /// Best-effort check for whether this artifact's package was built
+    /// straight from a local path, as opposed to pulled in as a
+    /// `registry`/`git` dependency. Used to keep dependency library
+    /// artifacts (which share a `Lib`/`Rlib`/... `TargetKind` with whatever
+    /// library the user is actually trying to inspect) out of the
+    /// `build`/`run` candidate list.
+    pub(crate) fn is_local_package(&self) -> bool {
+        !self.package_id.contains("registry+") && !self.package_id.contains("git+")
+    }
+
+    /// Best-effort guess at the `target/` directory containing this
+    /// artifact, found by walking up the artifact path looking for a
+    /// directory literally named `target` (Cargo's default). Returns
+    /// `None` if the artifact was built with a custom `CARGO_TARGET_DIR`
+    /// that doesn't use that name.
+    pub(crate) fn target_dir(&self) -> Option<&Path> {
+        self.filenames
+            .first()?
+            .ancestors()
+            .find(|p| p.file_name().is_some_and(|name| name == "target"))
+    }
+
+    /// Best-effort guess at the active profile name (`debug` or
+    /// `release`), inferred the same way cargo's own `target/<profile>/`
+    /// directory layout does.
+    pub(crate) fn profile_name(&self) -> &'static str {
+        if self.profile.opt_level == "0" && self.profile.debug_assertions {
+            "debug"
+        } else {
+            "release"
+        }
     }
 }
 
@@ -244,4 +743,55 @@ mod tests {
 }";
         let _opts: BuildOpt = serde_json::from_str(json).unwrap();
     }
+
+    #[test]
+    fn parse_runner_value_plain_string() {
+        assert_eq!(
+            parse_runner_value(r#""qemu-arm""#),
+            Some(vec!["qemu-arm".to_owned()])
+        );
+    }
+
+    #[test]
+    fn parse_runner_value_array() {
+        assert_eq!(
+            parse_runner_value(r#"["qemu-arm", "-L", "/usr/arm-linux-gnueabihf"]"#),
+            Some(vec![
+                "qemu-arm".to_owned(),
+                "-L".to_owned(),
+                "/usr/arm-linux-gnueabihf".to_owned(),
+            ])
+        );
+    }
+
+    #[test]
+    fn run_does_not_pick_up_the_crates_own_lib_target() {
+        // A crate with both a `src/lib.rs` and a `src/main.rs` produces a
+        // `bin` artifact depending on its own `lib` artifact; both are from
+        // the same local path package, so `run` must not treat the lib as a
+        // second runnable candidate alongside the bin.
+        let bin_json = "{\"features\":[],\"filenames\":[\"/repo/target/debug/demo\"],\"fresh\":true,\"package_id\":\"demo 0.1.0 (path+file:///repo)\",\"profile\":{\"debug_assertions\":true,\"debuginfo\":2,\"opt_level\":\"0\",\"overflow_checks\":true,\"test\":false},\"reason\":\"compiler-artifact\",\"target\":{\"crate_types\":[\"bin\"],\"edition\":\"2018\",\"kind\":[\"bin\"],\"name\":\"demo\",\"src_path\":\"/repo/src/main.rs\"}}";
+        let lib_json = "{\"features\":[],\"filenames\":[\"/repo/target/debug/libdemo.rlib\"],\"fresh\":true,\"package_id\":\"demo 0.1.0 (path+file:///repo)\",\"profile\":{\"debug_assertions\":true,\"debuginfo\":2,\"opt_level\":\"0\",\"overflow_checks\":true,\"test\":false},\"reason\":\"compiler-artifact\",\"target\":{\"crate_types\":[\"lib\"],\"edition\":\"2018\",\"kind\":[\"lib\"],\"name\":\"demo\",\"src_path\":\"/repo/src/lib.rs\"}}";
+        let opts: Vec<BuildOpt> = vec![
+            serde_json::from_str(bin_json).unwrap(),
+            serde_json::from_str(lib_json).unwrap(),
+        ];
+
+        let candidate = select_buildopt(&opts, CmdKind::Run, SelectFilter::default()).unwrap();
+        assert_eq!(candidate.target.kind, vec![TargetKind::Bin]);
+    }
+
+    #[test]
+    fn find_cargo_config_walks_up_to_parent() {
+        let root = std::env::temp_dir().join("cargo-with-test-find-cargo-config");
+        let nested = root.join("a").join("b");
+        std::fs::create_dir_all(&nested).unwrap();
+        std::fs::create_dir_all(root.join(".cargo")).unwrap();
+        let config_path = root.join(".cargo").join("config.toml");
+        std::fs::write(&config_path, "").unwrap();
+
+        assert_eq!(find_cargo_config(&nested), Some(config_path));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
 }
@@ -1,14 +1,19 @@
+extern crate serde;
+extern crate serde_json;
+#[macro_use]
+extern crate serde_derive;
+
 use std::process::Command;
 
 use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
-use failure::{err_msg, Error};
-use log::debug;
+use failure::{err_msg, format_err, Error};
+use log::{debug, info};
 use void::{unreachable, Void};
 
-mod cargo_command;
+mod cargo;
 mod with_command;
 
-use crate::cargo_command::CargoCmd;
+use crate::cargo::Cmd as CargoCmd;
 use crate::with_command::WithCmd;
 
 const COMMAND_NAME: &str = "with";
@@ -34,14 +39,111 @@ fn try_main() -> Result<Void, Error> {
 
     debug!("CLI matches: {:#?}", matches);
 
-    let (with_cmd, cargo_cmd) = process_matches(&matches)?;
-    // TODO: This should also be a void return type
-    let artifact_path = cargo_cmd.run()?.artifact()?;
-    let artifact = artifact_path
-        .to_str()
-        .ok_or_else(|| err_msg("Binary path is not valid utf-8"))?;
-    let mut finalized_with_cmd = with_cmd.child_command(artifact)?;
-    exec(&mut finalized_with_cmd)
+    let parsed = process_matches(&matches)?;
+    let target_triple = parsed.cargo_cmd.target_triple();
+    let buildopts = parsed.cargo_cmd.run()?;
+
+    let filter = cargo::SelectFilter {
+        crate_type: parsed.crate_type,
+        target_triple,
+        target_selector: parsed.cargo_cmd.target_selector(),
+        package: parsed.cargo_cmd.package_name(),
+    };
+    // Select the wanted buildopt(s). In `--all` mode, or when compiling
+    // tests/benches (which legitimately produce one binary per test/bench
+    // file), every matching candidate is run instead of erroring out on
+    // ambiguity.
+    let fan_out = parsed.all
+        || matches!(
+            parsed.cargo_cmd.kind(),
+            cargo::CmdKind::Test | cargo::CmdKind::Bench
+        );
+    let selected = if fan_out {
+        cargo::select_all_buildopts(&buildopts, parsed.cargo_cmd.kind(), filter)?
+    } else {
+        vec![cargo::select_buildopt(&buildopts, parsed.cargo_cmd.kind(), filter)?]
+    };
+
+    // When cross-compiling, the resulting binary often can't be executed
+    // natively. Fall back to the runner the user already configured for
+    // this target (`target.<triple>.runner` in `.cargo/config.toml`) and
+    // wrap the with-command in it.
+    let runner = target_triple.and_then(cargo::configured_runner);
+    let runner_prefix: Vec<&str> = runner.iter().flatten().map(String::as_str).collect();
+
+    // Same across every selected artifact, so these are derived once up
+    // front rather than per-iteration below.
+    let deps = cargo::dep_search_paths(&buildopts);
+    let cfgs = cargo::target_cfgs(target_triple).unwrap_or_default();
+
+    let mut failed_code = None;
+    let last_index = selected.len() - 1;
+    for (i, buildopt) in selected.into_iter().enumerate() {
+        let artifact_path = buildopt.artifact(parsed.crate_type)?;
+        let artifact = artifact_path
+            .to_str()
+            .ok_or_else(|| err_msg("Binary path is not valid utf-8"))?;
+
+        let target_dir = buildopt.target_dir().and_then(|p| p.to_str()).unwrap_or("");
+        let profile = buildopt.profile_name();
+        let package_name = buildopt.package_name();
+        let target_name = buildopt.target.name.as_str();
+        let placeholders = [
+            ("{target_dir}", target_dir),
+            ("{package_name}", package_name),
+            ("{bin_name}", target_name),
+            ("{example}", target_name),
+            ("{profile}", profile),
+        ];
+        let multi_placeholders: [(&str, &[String]); 2] =
+            [("{deps}", deps.as_slice()), ("{cfgs}", cfgs.as_slice())];
+        let mut finalized_with_cmd = parsed.with_cmd.child_command(
+            artifact,
+            &runner_prefix,
+            &placeholders,
+            &multi_placeholders,
+        )?;
+
+        // Make sibling artifacts in the same build locatable to wrappers
+        // and debugger scripts without having to re-derive the path.
+        finalized_with_cmd.env("CARGO_WITH_TARGET_DIR", target_dir);
+        for (key, value) in &parsed.envs {
+            finalized_with_cmd.env(key, value);
+        }
+        if let Some(workdir) = parsed.workdir {
+            finalized_with_cmd.current_dir(workdir);
+        }
+
+        // With a single artifact we can `exec` straight into it, replacing
+        // this process. With several, we have to spawn and wait so we can
+        // keep going after a failure and propagate the first non-zero code.
+        if !fan_out && i == last_index {
+            return exec(&mut finalized_with_cmd);
+        }
+
+        let code = spawn_and_wait(&mut finalized_with_cmd)?;
+        if code != 0 {
+            eprintln!("{} exited with code {}", buildopt.target.name, code);
+            if failed_code.is_none() {
+                failed_code = Some(code);
+            }
+        }
+    }
+
+    std::process::exit(failed_code.unwrap_or(0));
+}
+
+/// Everything extracted from the CLI matches, bundled up since the list of
+/// independent options keeps growing.
+struct ParsedArgs<'a> {
+    with_cmd: WithCmd,
+    cargo_cmd: CargoCmd<'a>,
+    all: bool,
+    crate_type: Option<cargo::CrateType>,
+    /// `KEY=VALUE` pairs from `--env`, applied to the spawned with-command.
+    envs: Vec<(&'a str, &'a str)>,
+    /// Working directory for the spawned with-command, from `--workdir`.
+    workdir: Option<&'a str>,
 }
 
 /// Process command line arguments. The input is split up into three
@@ -49,7 +151,7 @@ fn try_main() -> Result<Void, Error> {
 /// `<cargo-with-cmd> -- <cargo-command> -- <user-args>`
 /// Thus, the command `cargo with echo -- run -- my-args` is split up into
 /// `[echo]`, `[run]`, `[my-args]`
-fn process_matches<'a>(matches: &'a ArgMatches<'_>) -> Result<(WithCmd<'a>, CargoCmd<'a>), Error> {
+fn process_matches<'a>(matches: &'a ArgMatches<'_>) -> Result<ParsedArgs<'a>, Error> {
     // A prelude to work around the fact that this is run as `cargo
     // with` and not `cargo-with`
     let matches = matches
@@ -67,8 +169,37 @@ fn process_matches<'a>(matches: &'a ArgMatches<'_>) -> Result<(WithCmd<'a>, Carg
     let cargo_cmd = cargo_cmd_and_args.by_ref().take_while(|&el| el != "--");
     let cargo_cmd = CargoCmd::from_strs(cargo_cmd)?;
     let trailing_args: Vec<_> = cargo_cmd_and_args.collect();
-    let with_cmd = WithCmd::new(raw_with_cmd, &trailing_args);
-    Ok((with_cmd, cargo_cmd))
+    let with_cmd = WithCmd::new(raw_with_cmd, &trailing_args)?;
+    let all = matches.is_present("all");
+    let crate_type = matches
+        .value_of("crate-type")
+        .map(|s| {
+            cargo::CrateType::from_str(s)
+                .ok_or_else(|| format_err!("Unable to convert '{}' into a crate type", s))
+        })
+        .transpose()?;
+    let envs = matches
+        .values_of("env")
+        .into_iter()
+        .flatten()
+        .map(|kv| {
+            let mut parts = kv.splitn(2, '=');
+            let key = parts.next().unwrap();
+            let value = parts
+                .next()
+                .ok_or_else(|| format_err!("Invalid `--env {}`: expected KEY=VALUE", kv))?;
+            Ok((key, value))
+        })
+        .collect::<Result<_, Error>>()?;
+    let workdir = matches.value_of("workdir");
+    Ok(ParsedArgs {
+        with_cmd,
+        cargo_cmd,
+        all,
+        crate_type,
+        envs,
+        workdir,
+    })
 }
 
 fn create_app<'a, 'b>() -> App<'a, 'b> {
@@ -89,6 +220,33 @@ fn create_app<'a, 'b>() -> App<'a, 'b> {
             SubCommand::with_name(COMMAND_NAME)
                 .about(COMMAND_DESCRIPTION)
                 .arg(Arg::from_usage(&with_usage))
+                .arg(
+                    Arg::with_name("all")
+                        .long("all")
+                        .help("Run the with-command against every matching build artifact instead of erroring out when there is more than one"),
+                )
+                .arg(
+                    Arg::with_name("crate-type")
+                        .long("crate-type")
+                        .takes_value(true)
+                        .possible_values(&["lib", "rlib", "dylib", "cdylib", "staticlib"])
+                        .help("Select a library artifact (e.g. a `cdylib`) instead of an executable"),
+                )
+                .arg(
+                    Arg::with_name("env")
+                        .long("env")
+                        .takes_value(true)
+                        .number_of_values(1)
+                        .multiple(true)
+                        .value_name("KEY=VALUE")
+                        .help("Set an environment variable for the spawned command. May be given multiple times"),
+                )
+                .arg(
+                    Arg::with_name("workdir")
+                        .long("workdir")
+                        .takes_value(true)
+                        .help("Working directory for the spawned command"),
+                )
                 .arg(clap::Arg::from_usage(cargo_usage).raw(true))
                 .after_help(
                     r#"
@@ -96,26 +254,41 @@ EXAMPLES:
    cargo with echo -- run
    cargo with "gdb --args" -- run
    cargo with "echo {args} {bin}" -- test -- myargs
+   cargo with --all echo -- test
+   cargo with --env RUST_BACKTRACE=1 --workdir /tmp gdb -- run
 "#,
                 ),
         )
         .settings(&[AppSettings::SubcommandRequired])
 }
 
+/// Spawns `command`, waits for it to finish, and turns its exit status into
+/// a plain exit code. The single helper behind every non-`exec` spawn of
+/// the with-command: the multi-artifact loop (where we have to keep going
+/// after a failure instead of replacing this process) and the non-unix
+/// fallback for `exec` both go through this, so it is the one place that
+/// maps a failed spawn or a signal-terminated child into an `Error`. The
+/// unix `exec` logs the command the same way before replacing this process.
+fn spawn_and_wait(command: &mut Command) -> Result<i32, Error> {
+    info!("Executing {:?}", command);
+    let status = command
+        .status()
+        .map_err(|e| format_err!("Failed to spawn `{:?}`: {}", command, e))?;
+    status
+        .code()
+        .ok_or_else(|| err_msg("Child process was terminated by a signal"))
+}
+
 #[cfg(unix)]
 fn exec(command: &mut Command) -> Result<Void, Error> {
     use std::os::unix::process::CommandExt;
+    info!("Executing {:?}", command);
     Err(command.exec())?
 }
 
 #[cfg(not(unix))]
 fn exec(command: &mut Command) -> Result<Void, Error> {
-    std::process::exit(
-        command
-            .status()?
-            .code()
-            .expect("Process terminated by signal"),
-    )
+    std::process::exit(spawn_and_wait(command)?)
 }
 
 #[cfg(test)]
@@ -180,13 +353,24 @@ mod tests {
             for evoc in *evocs {
                 println!("Running {:?}", evoc);
                 let matches = create_app().get_matches_from(*evoc);
-                let (with_cmd, cargo_cmd) = process_matches(&matches).unwrap();
-                let artifact_path = cargo_cmd.run().unwrap().artifact().unwrap();
+                let parsed = process_matches(&matches).unwrap();
+                let filter = cargo::SelectFilter {
+                    crate_type: parsed.crate_type,
+                    target_triple: parsed.cargo_cmd.target_triple(),
+                    target_selector: parsed.cargo_cmd.target_selector(),
+                    package: parsed.cargo_cmd.package_name(),
+                };
+                let buildopts = parsed.cargo_cmd.run().unwrap();
+                let artifact_path =
+                    cargo::select_buildopt(&buildopts, parsed.cargo_cmd.kind(), filter)
+                        .unwrap()
+                        .artifact(parsed.crate_type)
+                        .unwrap();
                 let artifact = artifact_path
                     .to_str()
                     .ok_or_else(|| err_msg("Binary path is not valid utf-8"))
                     .unwrap();
-                let mut with_cmd = with_cmd.child_command(artifact).unwrap();
+                let mut with_cmd = parsed.with_cmd.child_command(artifact, &[], &[], &[]).unwrap();
                 with_cmd.current_dir(project_dir);
                 assert!(with_cmd.status().unwrap().success());
             }
@@ -199,13 +383,24 @@ mod tests {
             for evoc in *evocs {
                 println!("Running {:?}", evoc);
                 if let Ok(matches) = create_app().get_matches_from_safe(*evoc) {
-                    if let Ok((with_cmd, cargo_cmd)) = process_matches(&matches) {
-                        let artifact_path = cargo_cmd.run().unwrap().artifact().unwrap();
+                    if let Ok(parsed) = process_matches(&matches) {
+                        let filter = cargo::SelectFilter {
+                            crate_type: parsed.crate_type,
+                            target_triple: parsed.cargo_cmd.target_triple(),
+                            target_selector: parsed.cargo_cmd.target_selector(),
+                            package: parsed.cargo_cmd.package_name(),
+                        };
+                        let buildopts = parsed.cargo_cmd.run().unwrap();
+                        let artifact_path =
+                            cargo::select_buildopt(&buildopts, parsed.cargo_cmd.kind(), filter)
+                                .unwrap()
+                                .artifact(parsed.crate_type)
+                                .unwrap();
                         let artifact = artifact_path
                             .to_str()
                             .ok_or_else(|| err_msg("Binary path is not valid utf-8"))
                             .unwrap();
-                        let mut with_cmd = with_cmd.child_command(artifact).unwrap();
+                        let mut with_cmd = parsed.with_cmd.child_command(artifact, &[], &[], &[]).unwrap();
                         with_cmd.current_dir(project_dir);
 
                         assert!(with_cmd.output().is_err());
@@ -1,53 +1,136 @@
 use std::process::Command;
 
-use failure::{err_msg, Error};
+use failure::{format_err, Error};
 
-pub(crate) struct WithCmd<'a> {
-    // Raw command given as first argument; the {bin} placeholder is
-    // not yet replaced with the expanded value. The first element is
-    // the executable.
-    split_cmd: Vec<&'a str>,
+pub(crate) struct WithCmd {
+    // Raw command given as first argument; `{args}` is already expanded
+    // into the trailing arguments, all other placeholders are not yet
+    // replaced with their expanded values. The first element is the
+    // executable.
+    split_cmd: Vec<String>,
 }
 
-impl<'a> WithCmd<'a> {
-    /// Parse the command string which was passed in as the first
-    /// argument. Currently, we just split on whitespaces which is not
-    /// correct if there are quotes
-    pub fn new(raw: &'a str, trailing_args: &[&'a str]) -> Self {
+impl WithCmd {
+    /// Parse the command string which was passed in as the first argument
+    /// using POSIX-style word splitting, so quoted substrings (e.g. a gdb
+    /// script passed as `"gdb -ex 'break main'"`) survive as a single
+    /// argument instead of being torn apart on every whitespace.
+    pub fn new(raw: &str, trailing_args: &[&str]) -> Result<Self, Error> {
         // Example of a raw at this point "echo {bin} something"
-        // Splitting on whitspaces is bad but simple
-        let mut split_raw: Vec<_> = raw.split_whitespace().collect();
+        let mut split_raw = shell_words::split(raw)
+            .map_err(|_| format_err!("Unable to parse with-command `{}`: unmatched quote", raw))?;
         // Make sure that we have {bin} and {args} somewhere. We look
         // for it in the original string to not get into trouble with
-        // a whitespaces.
+        // quoting/whitespace.
         if !raw.contains("{bin}") {
-            split_raw.push("{bin}");
+            split_raw.push("{bin}".to_owned());
         }
         if !raw.contains("{args}") {
-            split_raw.push("{args}");
+            split_raw.push("{args}".to_owned());
         }
         // Construct final split args and replace {args}
         let mut split_cmd = vec![];
         for el in split_raw {
             if el == "{args}" {
-                split_cmd.extend_from_slice(&trailing_args);
+                split_cmd.extend(trailing_args.iter().map(|&el| el.to_owned()));
             } else {
                 split_cmd.push(el);
             }
         }
-        Self { split_cmd }
+        Ok(Self { split_cmd })
     }
 
-    /// Produce the ready-to-execute `Command` struct with all
-    /// occurrences of {bin} and {args} replaced
-    pub fn child_command(&self, bin_path: &str) -> Result<Command, Error> {
-        if let Some((bin, args)) = self.split_cmd.split_first() {
-            let replaced_args = args.iter().map(|el| el.replace("{bin}", bin_path));
-            let mut cmd = Command::new(bin.replace("{bin}", bin_path));
+    /// Produce the ready-to-execute `Command` struct with all placeholders
+    /// replaced: `{bin}` with `bin_path`, any of `placeholders` (e.g.
+    /// `{target_dir}`, `{package_name}`, `{bin_name}`/`{example}`,
+    /// `{profile}`) substituted as part of whatever argv element they
+    /// appear in, and any of `multi_placeholders` (e.g. `{deps}`, `{cfgs}`)
+    /// that make up an entire argv element on their own expanded into
+    /// their several values as that many distinct `Command` arguments
+    /// (never joined into one, since there's no shell downstream to
+    /// re-split an embedded-space string).
+    ///
+    /// When `runner_prefix` is non-empty (typically the configured
+    /// `target.<triple>.runner` for a foreign `--target`), it is prepended
+    /// in front of the with-command, e.g. `qemu-arm gdb --args {bin}`
+    /// instead of just `gdb --args {bin}`.
+    pub fn child_command(
+        &self,
+        bin_path: &str,
+        runner_prefix: &[&str],
+        placeholders: &[(&str, &str)],
+        multi_placeholders: &[(&str, &[String])],
+    ) -> Result<Command, Error> {
+        let expand_scalar = |el: &str| -> String {
+            let mut el = el.replace("{bin}", bin_path);
+            for (token, value) in placeholders {
+                el = el.replace(token, value);
+            }
+            el
+        };
+        let expand_arg = |el: &str| -> Vec<String> {
+            for (token, values) in multi_placeholders {
+                if el == *token {
+                    return values.to_vec();
+                }
+            }
+            vec![expand_scalar(el)]
+        };
+
+        let (bin, args) = self
+            .split_cmd
+            .split_first()
+            .ok_or_else(|| format_err!("No child command given."))?;
+        let replaced_bin = expand_scalar(bin);
+        let replaced_args: Vec<_> = args.iter().flat_map(|el| expand_arg(el)).collect();
+
+        if let Some((runner_bin, runner_args)) = runner_prefix.split_first() {
+            let mut cmd = Command::new(runner_bin);
+            cmd.args(runner_args);
+            cmd.arg(replaced_bin);
             cmd.args(replaced_args);
             Ok(cmd)
         } else {
-            Err(err_msg("No child command given."))
+            let mut cmd = Command::new(replaced_bin);
+            cmd.args(replaced_args);
+            Ok(cmd)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(cmd: &Command) -> Vec<&str> {
+        cmd.get_args().map(|a| a.to_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn quoted_substrings_survive_as_one_argument() {
+        let with_cmd = WithCmd::new("gdb -ex 'break main' --args {bin}", &[]).unwrap();
+        let cmd = with_cmd
+            .child_command("/bin/my-bin", &[], &[], &[])
+            .unwrap();
+        assert_eq!(cmd.get_program().to_str().unwrap(), "gdb");
+        assert_eq!(
+            args(&cmd),
+            vec!["-ex", "break main", "--args", "/bin/my-bin"]
+        );
+    }
+
+    #[test]
+    fn bin_and_args_are_appended_when_omitted() {
+        let with_cmd = WithCmd::new("echo", &["extra"]).unwrap();
+        let cmd = with_cmd
+            .child_command("/bin/my-bin", &[], &[], &[])
+            .unwrap();
+        assert_eq!(cmd.get_program().to_str().unwrap(), "echo");
+        assert_eq!(args(&cmd), vec!["/bin/my-bin", "extra"]);
+    }
+
+    #[test]
+    fn unmatched_quote_is_rejected() {
+        assert!(WithCmd::new("echo 'unterminated", &[]).is_err());
+    }
+}